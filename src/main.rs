@@ -1,3 +1,6 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+
 use anyhow::{Context, Result};
 use aws_config::meta::region::RegionProviderChain;
 use aws_config::BehaviorVersion;
@@ -6,17 +9,19 @@ use aws_sdk_sts::operation::assume_role::builders::AssumeRoleFluentBuilder;
 use aws_sdk_sts::types::PolicyDescriptorType;
 use aws_sdk_sts::Client;
 use aws_smithy_runtime::client::http::hyper_014::HyperClientBuilder;
+use chrono::{DateTime, Duration, Utc};
 use hyper::client::HttpConnector;
 // use aws_smithy_runtime_api::client::behavior_version::BehaviorVersion;
 // use aws_smithy_runtime_api::client::http::HttpConnector;
 use serde::Serialize;
 
 use hyper::Uri;
-use hyper_proxy::{Intercept, Proxy, ProxyConnector};
+use hyper_proxy::{Custom, Intercept, Proxy, ProxyConnector};
+use hyper_rustls::HttpsConnector;
 use tracing_subscriber::EnvFilter;
 
 use crate::credential_file::CredentialFile;
-use crate::settings::Cmdline;
+use crate::settings::{Cmdline, OutputFormat};
 
 mod credential_file;
 mod settings;
@@ -53,7 +58,7 @@ macro_rules! vprintln {
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let cmdline = Cmdline::parse();
+    let mut cmdline = Cmdline::parse();
 
     if cmdline.verbose {
         // Enable verbose tracing for AWS SDK HTTP traffic
@@ -64,31 +69,60 @@ async fn main() -> Result<()> {
 
     vprintln!(&cmdline, "Assuming role {}", cmdline.role_arn);
 
-    let sts_client = build_sts_client(&cmdline).await?;
-    let assume_role_request = build_assume_role_request(sts_client, &cmdline);
-    let result = assume_role_request
-        .send()
-        .await
-        .context("assume role failed")?;
+    if cmdline.credential_process {
+        if let Some(cache_path) = &cmdline.credential_process_cache {
+            if let Some(cached) = read_cached_credentials(cache_path) {
+                vprintln!(&cmdline, "Using cached credentials from {}", cache_path);
+                println!("{cached}");
+                return Ok(());
+            }
+        }
+    }
 
-    let credential_filename = cmdline.determine_credential_file()?;
-    vprintln!(
-        &cmdline,
-        "Assume role succeeded, saving credentials to {}",
-        credential_filename.display()
-    );
+    let config = load_aws_config(&cmdline).await;
 
-    let credentials = result
-        .credentials()
-        .with_context(|| "no credentials in response")?;
+    if cmdline.web_identity_token_file.is_none()
+        && cmdline.mfa_token.is_some()
+        && cmdline.mfa_serial_number.is_none()
+    {
+        let serial_number = discover_mfa_serial(&cmdline, &config).await?;
+        vprintln!(&cmdline, "Discovered MFA device {serial_number}");
+        cmdline.mfa_serial_number = Some(serial_number);
+    }
 
-    if cmdline.credential_process {
+    let sts_client = build_sts_client(&cmdline, &config, None).await?;
+    let mut credentials = assume_role(sts_client, &cmdline).await?;
+
+    for role_arn in &cmdline.chain {
+        vprintln!(&cmdline, "Assuming chained role {role_arn}");
+        let hop_client = build_sts_client(&cmdline, &config, Some(&credentials)).await?;
+        credentials = assume_chain_hop(hop_client, &cmdline, role_arn).await?;
+    }
+
+    vprintln!(&cmdline, "Assume role succeeded");
+
+    let credentials = &credentials;
+
+    if cmdline.exec {
+        return exec_with_credentials(&cmdline, &config, credentials);
+    }
+
+    if let Some(format) = cmdline.format {
+        print_shell_exports(&config, format, credentials);
+    } else if cmdline.credential_process {
         let output = CredentialProcessOutput::from_credentials(credentials);
-        println!(
-            "{}",
-            serde_json::to_string(&output).context("failed to serialize credentials")?
-        );
+        let json = serde_json::to_string(&output).context("failed to serialize credentials")?;
+        if let Some(cache_path) = &cmdline.credential_process_cache {
+            write_cached_credentials(cache_path, &json)?;
+        }
+        println!("{json}");
     } else {
+        let credential_filename = cmdline.determine_credential_file()?;
+        vprintln!(
+            &cmdline,
+            "Saving credentials to {}",
+            credential_filename.display()
+        );
         let mut credential_file = CredentialFile::load(&credential_filename)?;
         credential_file.set_credentials(&cmdline.dest_profile, credentials);
         credential_file.save(&credential_filename)?;
@@ -97,7 +131,243 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
-async fn build_sts_client(cmdline: &Cmdline) -> Result<Client> {
+/// Assume a single role in a chain. Only the role ARN, session name and
+/// duration apply here; MFA and external-id are handled on the first hop.
+async fn assume_chain_hop(
+    client: Client,
+    cmdline: &Cmdline,
+    role_arn: &str,
+) -> Result<aws_sdk_sts::types::Credentials> {
+    let mut builder = client
+        .assume_role()
+        .role_arn(role_arn)
+        .role_session_name(cmdline.session_name());
+
+    if let Some(duration_seconds) = cmdline.duration {
+        builder = builder.duration_seconds(duration_seconds);
+    }
+
+    let result = builder
+        .send()
+        .await
+        .context("assume role (chain) failed")?;
+    result
+        .credentials()
+        .cloned()
+        .with_context(|| "no credentials in response")
+}
+
+/// Look up the current user's MFA device serial number via `ListMFADevices`,
+/// using the same base config and proxy settings as the STS client. Errors when
+/// the user has no MFA device or more than one, since we cannot guess which to use.
+async fn discover_mfa_serial(
+    cmdline: &Cmdline,
+    config: &aws_config::SdkConfig,
+) -> Result<String> {
+    let mut iam_config_builder = aws_sdk_iam::config::Builder::from(config);
+    if let Some(proxy_uri) = resolve_proxy_uri(cmdline) {
+        let proxy_connector = build_proxy_connector(cmdline, &proxy_uri)?;
+        let http_client = HyperClientBuilder::new().build(proxy_connector);
+        iam_config_builder = iam_config_builder.http_client(http_client);
+    }
+    let iam_client = aws_sdk_iam::Client::from_conf(iam_config_builder.build());
+    let result = iam_client
+        .list_mfa_devices()
+        .send()
+        .await
+        .context("failed to list MFA devices")?;
+
+    match result.mfa_devices() {
+        [device] => Ok(device.serial_number().to_string()),
+        [] => anyhow::bail!("no MFA devices found for the current user; pass --mfa-serial-number"),
+        _ => anyhow::bail!(
+            "multiple MFA devices found for the current user; pass --mfa-serial-number"
+        ),
+    }
+}
+
+/// Spawn a child process with the temporary credentials injected as the usual
+/// `AWS_*` environment variables and exit with the child's exit code. When no
+/// command is given, launch an interactive login shell instead.
+fn exec_with_credentials(
+    cmdline: &Cmdline,
+    config: &aws_config::SdkConfig,
+    credentials: &aws_sdk_sts::types::Credentials,
+) -> Result<()> {
+    let mut args = cmdline.command.iter();
+    let program = match args.next() {
+        Some(program) => program.clone(),
+        None => default_shell(),
+    };
+
+    let mut command = std::process::Command::new(&program);
+    if cmdline.command.is_empty() && cfg!(not(windows)) {
+        command.arg("-l");
+    }
+    command.args(args);
+    command.env("AWS_ACCESS_KEY_ID", credentials.access_key_id());
+    command.env("AWS_SECRET_ACCESS_KEY", credentials.secret_access_key());
+    command.env("AWS_SESSION_TOKEN", credentials.session_token());
+    if let Some(region) = config.region() {
+        command.env("AWS_REGION", region.as_ref());
+    }
+
+    let status = command
+        .status()
+        .with_context(|| format!("failed to execute {program}"))?;
+
+    std::process::exit(status.code().unwrap_or(1));
+}
+
+/// Print the temporary credentials as shell export statements for the selected
+/// dialect, so users can `eval "$(assume-role ...)"` into their current shell.
+fn print_shell_exports(
+    config: &aws_config::SdkConfig,
+    format: OutputFormat,
+    credentials: &aws_sdk_sts::types::Credentials,
+) {
+    let mut vars = vec![
+        ("AWS_ACCESS_KEY_ID", credentials.access_key_id()),
+        ("AWS_SECRET_ACCESS_KEY", credentials.secret_access_key()),
+        ("AWS_SESSION_TOKEN", credentials.session_token()),
+    ];
+    if let Some(region) = config.region() {
+        vars.push(("AWS_REGION", region.as_ref()));
+    }
+
+    for (name, value) in vars {
+        match format {
+            OutputFormat::Env => println!("export {name}={value}"),
+            OutputFormat::PowerShell => println!("$env:{name} = \"{value}\""),
+            OutputFormat::Windows => println!("set {name}={value}"),
+        }
+    }
+}
+
+/// Determine the shell to launch when `--exec` is used without a command.
+fn default_shell() -> String {
+    #[cfg(windows)]
+    {
+        std::env::var("ComSpec").unwrap_or_else(|_| "cmd.exe".to_string())
+    }
+    #[cfg(not(windows))]
+    {
+        std::env::var("SHELL").unwrap_or_else(|_| "/bin/sh".to_string())
+    }
+}
+
+/// Perform the assume-role call, selecting the web-identity flow when a token
+/// file is configured and the standard flow otherwise. Both flows funnel their
+/// STS credentials through the same output paths in `main`.
+async fn assume_role(client: Client, cmdline: &Cmdline) -> Result<aws_sdk_sts::types::Credentials> {
+    if let Some(token_file) = &cmdline.web_identity_token_file {
+        assume_role_with_web_identity(client, cmdline, token_file).await
+    } else {
+        let result = build_assume_role_request(client, cmdline)
+            .send()
+            .await
+            .context("assume role failed")?;
+        result
+            .credentials()
+            .cloned()
+            .with_context(|| "no credentials in response")
+    }
+}
+
+/// Call `AssumeRoleWithWebIdentity` using the JWT read from `token_file`. MFA,
+/// external id and source-credential handling do not apply to web-identity
+/// federation and are intentionally skipped here.
+async fn assume_role_with_web_identity(
+    client: Client,
+    cmdline: &Cmdline,
+    token_file: &str,
+) -> Result<aws_sdk_sts::types::Credentials> {
+    let token = std::fs::read_to_string(token_file)
+        .with_context(|| format!("unable to read web identity token file {token_file}"))?;
+
+    let mut builder = client
+        .assume_role_with_web_identity()
+        .role_arn(&cmdline.role_arn)
+        .role_session_name(cmdline.session_name())
+        .web_identity_token(token.trim());
+
+    if let Some(duration_seconds) = cmdline.duration {
+        builder = builder.duration_seconds(duration_seconds);
+    }
+
+    let result = builder
+        .send()
+        .await
+        .context("assume role with web identity failed")?;
+    result
+        .credentials()
+        .cloned()
+        .with_context(|| "no credentials in response")
+}
+
+/// Safety margin before a cached credential's expiration during which we treat
+/// it as already stale and re-assume the role.
+const CACHE_SAFETY_MARGIN_MINUTES: i64 = 5;
+
+/// Read a previously cached `CredentialProcessOutput` JSON document and return
+/// it verbatim when it is still valid for at least `CACHE_SAFETY_MARGIN_MINUTES`.
+/// Any error (missing file, bad JSON, unparsable or expired timestamp) is
+/// treated as a cache miss so that `main` falls back to a fresh assume-role.
+fn read_cached_credentials<P: AsRef<Path>>(path: P) -> Option<String> {
+    let contents = std::fs::read_to_string(path).ok()?;
+    let parsed: serde_json::Value = serde_json::from_str(&contents).ok()?;
+    let expiration = parsed.get("Expiration")?.as_str()?;
+    let expiration = DateTime::parse_from_rfc3339(expiration)
+        .ok()?
+        .with_timezone(&Utc);
+    if expiration > Utc::now() + Duration::minutes(CACHE_SAFETY_MARGIN_MINUTES) {
+        Some(contents)
+    } else {
+        None
+    }
+}
+
+/// Atomically write the serialized credential JSON to the cache path, creating
+/// any missing parent directories and restricting the file to `0600` so the
+/// temporary credentials are not world-readable.
+fn write_cached_credentials<P: AsRef<Path>>(path: P, json: &str) -> Result<()> {
+    let path = path.as_ref();
+    if let Some(parent) = path.parent() {
+        if !parent.as_os_str().is_empty() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("unable to create cache directory {}", parent.display()))?;
+        }
+    }
+
+    // Give each writer a unique temp name so concurrent `credential_process`
+    // helpers (which the AWS SDK spawns in parallel against the same cache path)
+    // each rename their own complete file rather than corrupting a shared inode.
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let unique = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let tmp_path = path.with_extension(format!("tmp.{}.{}", std::process::id(), unique));
+    {
+        use std::io::Write;
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options
+            .open(&tmp_path)
+            .with_context(|| format!("unable to create cache file {}", tmp_path.display()))?;
+        file.write_all(json.as_bytes())
+            .with_context(|| format!("unable to write cache file {}", tmp_path.display()))?;
+    }
+
+    std::fs::rename(&tmp_path, path)
+        .with_context(|| format!("unable to persist cache file {}", path.display()))?;
+
+    Ok(())
+}
+
+async fn load_aws_config(cmdline: &Cmdline) -> aws_config::SdkConfig {
     let region_provider = RegionProviderChain::first_try(cmdline.region.clone().map(Region::new))
         .or_default_provider()
         .or_else(Region::new("us-east-1"));
@@ -107,16 +377,28 @@ async fn build_sts_client(cmdline: &Cmdline) -> Result<Client> {
         config_loader = config_loader.profile_name(profile_name);
     }
 
-    let config = config_loader.load().await;
+    config_loader.load().await
+}
+
+async fn build_sts_client(
+    cmdline: &Cmdline,
+    config: &aws_config::SdkConfig,
+    credentials: Option<&aws_sdk_sts::types::Credentials>,
+) -> Result<Client> {
+    let mut sts_config_builder = aws_sdk_sts::config::Builder::from(config);
 
-    let mut sts_config_builder = aws_sdk_sts::config::Builder::from(&config);
-    if let Some(proxy_uri) = &cmdline.proxy {
-        let proxy = Proxy::new(
-            Intercept::All,
-            Uri::try_from(proxy_uri).context("invalid proxy_uri")?,
+    if let Some(credentials) = credentials {
+        let provider = aws_sdk_sts::config::Credentials::new(
+            credentials.access_key_id(),
+            credentials.secret_access_key(),
+            Some(credentials.session_token().to_string()),
+            None,
+            "assume-role-chain",
         );
-        let connector = HttpConnector::new();
-        let proxy_connector = ProxyConnector::from_proxy(connector, proxy)?;
+        sts_config_builder = sts_config_builder.credentials_provider(provider);
+    }
+    if let Some(proxy_uri) = resolve_proxy_uri(cmdline) {
+        let proxy_connector = build_proxy_connector(cmdline, &proxy_uri)?;
         let http_client = HyperClientBuilder::new().build(proxy_connector);
         sts_config_builder = sts_config_builder.http_client(http_client);
     }
@@ -126,6 +408,112 @@ async fn build_sts_client(cmdline: &Cmdline) -> Result<Client> {
     Ok(sts_client)
 }
 
+/// Resolve the proxy URI from `--proxy`, falling back to the standard
+/// `HTTPS_PROXY`/`https_proxy` environment variables used by other AWS tooling.
+fn resolve_proxy_uri(cmdline: &Cmdline) -> Option<String> {
+    cmdline
+        .proxy
+        .clone()
+        .or_else(|| std::env::var("HTTPS_PROXY").ok())
+        .or_else(|| std::env::var("https_proxy").ok())
+}
+
+/// Build an HTTPS-capable proxy connector so we can establish TLS both to an
+/// HTTPS proxy and to the STS endpoint behind it. Honors a custom CA bundle,
+/// proxy credentials embedded in the URI, and the `NO_PROXY` exclusion list.
+fn build_proxy_connector(
+    cmdline: &Cmdline,
+    proxy_uri: &str,
+) -> Result<ProxyConnector<HttpsConnector<HttpConnector>>> {
+    let uri = Uri::try_from(proxy_uri).context("invalid proxy_uri")?;
+    let mut proxy = Proxy::new(no_proxy_intercept(), uri);
+
+    if let Some((username, password)) = proxy_credentials(proxy_uri) {
+        proxy.set_authorization(headers::Authorization::basic(&username, &password));
+    }
+
+    let https = build_https_connector(cmdline)?;
+    ProxyConnector::from_proxy(https, proxy).context("unable to build proxy connector")
+}
+
+/// Build the TLS connector, trusting the platform's native roots plus, when
+/// given, the certificates of a user-supplied CA bundle.
+fn build_https_connector(cmdline: &Cmdline) -> Result<HttpsConnector<HttpConnector>> {
+    let mut http = HttpConnector::new();
+    http.enforce_http(false);
+
+    let builder = hyper_rustls::HttpsConnectorBuilder::new();
+    let builder = match &cmdline.proxy_ca_bundle {
+        Some(ca_bundle) => {
+            let pem = std::fs::read(ca_bundle)
+                .with_context(|| format!("unable to read CA bundle {ca_bundle}"))?;
+            // Seed the trust store with the platform's native roots so TLS to the
+            // real STS endpoint still validates, then add the custom CA on top
+            // (it typically only covers the intercepting proxy itself).
+            let mut roots = rustls::RootCertStore::empty();
+            for cert in rustls_native_certs::load_native_certs()
+                .context("unable to load native root certificates")?
+            {
+                roots
+                    .add(&rustls::Certificate(cert.0))
+                    .context("unable to add native root certificate to trust store")?;
+            }
+            let certs = rustls_pemfile::certs(&mut &pem[..])
+                .with_context(|| format!("unable to parse CA bundle {ca_bundle}"))?;
+            for cert in certs {
+                roots
+                    .add(&rustls::Certificate(cert))
+                    .context("unable to add CA certificate to trust store")?;
+            }
+            let tls = rustls::ClientConfig::builder()
+                .with_safe_defaults()
+                .with_root_certificates(roots)
+                .with_no_client_auth();
+            builder.with_tls_config(tls)
+        }
+        None => builder.with_native_roots(),
+    };
+
+    Ok(builder.https_or_http().enable_http1().wrap_connector(http))
+}
+
+/// Parse `user:password` credentials out of the userinfo portion of a proxy URI.
+fn proxy_credentials(proxy_uri: &str) -> Option<(String, String)> {
+    let without_scheme = proxy_uri.split("://").nth(1).unwrap_or(proxy_uri);
+    let userinfo = without_scheme.split('@').next().filter(|_| without_scheme.contains('@'))?;
+    let (username, password) = userinfo.split_once(':')?;
+    Some((username.to_string(), password.to_string()))
+}
+
+/// Build a proxy `Intercept` that skips hosts listed in `NO_PROXY`/`no_proxy`.
+fn no_proxy_intercept() -> Intercept {
+    let no_proxy = std::env::var("NO_PROXY")
+        .or_else(|_| std::env::var("no_proxy"))
+        .ok();
+
+    match no_proxy {
+        Some(list) => {
+            let excluded: Vec<String> = list
+                .split(',')
+                .map(|entry| entry.trim().trim_start_matches('.').to_lowercase())
+                .filter(|entry| !entry.is_empty())
+                .collect();
+            Intercept::Custom(Custom::from(
+                move |_scheme: Option<&str>, host: Option<&str>, _port: Option<u16>| match host {
+                    Some(host) => {
+                        let host = host.to_lowercase();
+                        !excluded
+                            .iter()
+                            .any(|entry| host == *entry || host.ends_with(&format!(".{entry}")))
+                    }
+                    None => true,
+                },
+            ))
+        }
+        None => Intercept::All,
+    }
+}
+
 fn build_assume_role_request(client: Client, cmdline: &Cmdline) -> AssumeRoleFluentBuilder {
     let mut builder = client.assume_role();
 
@@ -160,6 +548,6 @@ fn build_assume_role_request(client: Client, cmdline: &Cmdline) -> AssumeRoleFlu
     }
 
     builder = builder.role_arn(&cmdline.role_arn);
-    builder = builder.role_session_name(&cmdline.session_name);
+    builder = builder.role_session_name(cmdline.session_name());
     builder
 }
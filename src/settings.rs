@@ -38,13 +38,18 @@ pub struct Cmdline {
     pub policies: Option<Vec<String>>,
 
     /// ARN of role ot assume
-    #[structopt(name = "role", long, short = "r")]
+    #[structopt(name = "role", long, short = "r", env = "AWS_ROLE_ARN")]
     pub role_arn: String,
 
     /// Session name to pass to assume-role
     #[structopt(name = "session-name", long, short = "s")]
     pub session_name: Option<String>,
 
+    /// Additional role ARN(s) to assume in order after --role, feeding each
+    /// hop's credentials into the next (MFA/external-id apply to --role only)
+    #[structopt(name = "chain", long)]
+    pub chain: Vec<String>,
+
     /// MFA device serial number
     #[structopt(name = "mfa-serial-number", long)]
     pub mfa_serial_number: Option<String>,
@@ -53,6 +58,14 @@ pub struct Cmdline {
     #[structopt(name = "mfa", long)]
     pub mfa_token: Option<String>,
 
+    /// Path to an OIDC/JWT token file for assume-role-with-web-identity
+    #[structopt(
+        name = "web-identity-token-file",
+        long,
+        env = "AWS_WEB_IDENTITY_TOKEN_FILE"
+    )]
+    pub web_identity_token_file: Option<String>,
+
     /// Credential file to save new credentials to
     #[structopt(name = "credentials-file", long, env = "AWS_SHARED_CREDENTIALS_FILE")]
     pub credential_file: Option<String>,
@@ -61,10 +74,22 @@ pub struct Cmdline {
     #[structopt(name = "dest-profile", long, default_value = "default")]
     pub dest_profile: String,
 
-    /// Proxy URL
+    /// Proxy URL (falls back to the HTTPS_PROXY environment variable)
     #[structopt(name = "proxy", long)]
     pub proxy: Option<String>,
 
+    /// PEM bundle of CA certificates to trust for the STS/proxy TLS connection
+    #[structopt(name = "proxy-ca-bundle", long)]
+    pub proxy_ca_bundle: Option<String>,
+
+    /// Launch a command with the temporary credentials in its environment instead of saving them
+    #[structopt(name = "exec", long)]
+    pub exec: bool,
+
+    /// Command (and arguments) to run in --exec mode; defaults to an interactive shell
+    #[structopt(name = "command", last = true)]
+    pub command: Vec<String>,
+
     /// Print credentials in Process Credential Provider format instead of saving to a file
     #[structopt(name = "credential-process", long)]
     pub credential_process: bool,
@@ -73,11 +98,44 @@ pub struct Cmdline {
     #[structopt(name = "credential-process-cache", long)]
     pub credential_process_cache: Option<String>,
 
+    /// Print shell export statements for `eval` instead of saving to a file
+    #[structopt(
+        name = "format",
+        long,
+        possible_values = &["env", "powershell", "windows"],
+        conflicts_with_all = &["credential-process", "credential-process-cache"]
+    )]
+    pub format: Option<OutputFormat>,
+
     /// Enable verbose output
     #[structopt(short, long)]
     pub verbose: bool,
 }
 
+/// Shell dialect for the `--format` export output.
+#[derive(Debug, Clone, Copy)]
+pub enum OutputFormat {
+    /// POSIX `export NAME=value` statements
+    Env,
+    /// PowerShell `$env:NAME = "value"` statements
+    PowerShell,
+    /// Windows `cmd.exe` `set NAME=value` statements
+    Windows,
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<OutputFormat> {
+        match s {
+            "env" => Ok(OutputFormat::Env),
+            "powershell" => Ok(OutputFormat::PowerShell),
+            "windows" => Ok(OutputFormat::Windows),
+            other => Err(anyhow::anyhow!("unknown output format: {other}")),
+        }
+    }
+}
+
 impl Cmdline {
     pub fn parse() -> Cmdline {
         Cmdline::from_args()